@@ -1,139 +1,2478 @@
-use std::time::Instant;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use axum::{
     body::Body,
-    extract::{Path, Request, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
     middleware::{self, Next},
-    response::{IntoResponse, Redirect, Response},
-    routing::{get, post},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
-use heed::{types::Str, Database, Env, EnvOpenOptions};
+use byteorder::BigEndian;
+use heed::{
+    types::{DecodeIgnore, SerdeJson, Str, U64},
+    Database, Env, EnvOpenOptions, MdbError, RoTxn, RwTxn,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use subtle::ConstantTimeEq;
+use tempfile::TempDir;
+use tokio::{
+    signal,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinError,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tower::ServiceBuilder;
-use tracing::{info, Level};
+use tower_http::request_id::{
+    MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
+use tracing::{info, Instrument, Level};
 use tracing_subscriber::FmtSubscriber;
+use url::Url;
+
+/// Address to listen on when `SILLY_BIND_ADDR` is unset.
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:3000";
+
+/// Default location of the LMDB environment when `SILLY_DATA_DIR` is unset.
+const DEFAULT_DATA_DIR: &str = "./data";
+
+/// Length of aliases generated when the client doesn't supply one.
+const GENERATED_ALIAS_LEN: usize = 6;
+
+/// Longest alias accepted when `SILLY_MAX_ALIAS_LEN` is unset.
+const DEFAULT_MAX_ALIAS_LEN: usize = 64;
+
+/// How many random aliases to try before giving up on a create.
+const MAX_ALIAS_ATTEMPTS: usize = 5;
+
+/// Number of links returned by listing endpoints when `?limit=` is omitted.
+const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// Largest `?limit=` honoured by listing endpoints; bigger values are clamped.
+const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Creates allowed per client IP per minute when `SILLY_RATE_LIMIT` is unset.
+const DEFAULT_RATE_LIMIT: u32 = 10;
+
+/// Links created through `POST /api/links/batch` per client IP per minute when
+/// `SILLY_BATCH_RATE_LIMIT` is unset.
+const DEFAULT_BATCH_RATE_LIMIT: u32 = 1000;
+
+/// Most links accepted by a single `POST /api/links/batch`.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Bucket count above which fully refilled buckets are pruned.
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+
+/// LMDB databases available when `SILLY_MAX_DBS` is unset. Two are taken by
+/// `requests` and `clicks`; the rest hold namespaces.
+const DEFAULT_MAX_DBS: u32 = 32;
+
+/// Size of the LMDB memory map when `SILLY_MAP_SIZE` is unset. Only pages
+/// actually written take up disk, so this is a ceiling rather than a cost.
+const DEFAULT_MAP_SIZE: usize = 1 << 30;
+
+/// Prefix of the LMDB database names backing namespaces.
+const NAMESPACE_DB_PREFIX: &str = "ns:";
+
+/// Namespaces that would be shadowed by other routes' first path segment.
+const RESERVED_NAMESPACES: &[&str] = &["api", "stats"];
+
+/// Aliases that would be shadowed by single-segment routes.
+const RESERVED_ALIASES: &[&str] = &["healthz", "metrics"];
+
+/// Clicks queued for the click writer before further ones are dropped.
+const CLICK_QUEUE_CAPACITY: usize = 10_000;
+
+/// How often the click writer commits the counts it has summed.
+const CLICK_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the background task sweeps expired links out of the db.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often histogram buffers backing `/metrics` are drained.
+const METRICS_UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Histogram buckets, in seconds, for `silly_request_duration_seconds`.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
 
 #[derive(Clone)]
 struct AppState {
     /// The LMDB environment (wrapped in an Arc for thread safety)
     env: Arc<Env>,
-    /// The LMDB database to store our requests
-    db: Database<Str, Str>,
+    /// The LMDB database to store our requests (the default namespace)
+    db: LinkDb,
+    /// Databases of named namespaces, opened at startup or lazily on first write
+    namespaces: Arc<RwLock<HashMap<String, LinkDb>>>,
+    /// The LMDB database counting resolves per alias
+    clicks: Database<Str, U64<BigEndian>>,
+    /// Click keys waiting for `click_writer` to count them
+    click_queue: mpsc::Sender<String>,
+    /// Tracks blocking db writes so shutdown can wait for them to finish
+    writes: TaskTracker,
+    /// How aliases are normalised before they're stored or looked up
+    aliases: AliasRules,
+    /// Configured prefix for `ShortUrl.short_url`; derived per request if unset
+    base_url: Option<Url>,
+    /// Honour `X-Forwarded-*` headers from the proxy in front of us
+    trust_proxy: bool,
+    /// Renders the Prometheus metrics served at `/metrics`
+    metrics: PrometheusHandle,
+    /// Throttles batch imports, charged per link inside the handler
+    batch_limiter: Arc<RateLimiter>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let path = tempfile::tempdir()?;
+    init_tracing()?;
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-    info!("go.silly serving on port 3000....");
+    // Validate the listen address up front rather than failing inside bind.
+    let addr = bind_addr()?;
+    let limiter = Arc::new(RateLimiter::from_env(
+        "SILLY_RATE_LIMIT",
+        DEFAULT_RATE_LIMIT,
+    )?);
+    let batch_limiter = RateLimiter::from_env("SILLY_BATCH_RATE_LIMIT", DEFAULT_BATCH_RATE_LIMIT)?;
+    let aliases = AliasRules::from_env()?;
+    let base_url = configured_base_url()?;
+    let max_dbs = max_dbs()?;
+    let map_size = map_size()?;
+    let metrics = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("silly_request_duration_seconds".to_string()),
+            LATENCY_BUCKETS,
+        )?
+        .install_recorder()?;
+    let admin_token: Option<Arc<str>> = std::env::var("SILLY_ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Arc::from);
+    if admin_token.is_none() {
+        tracing::warn!(
+            "SILLY_ADMIN_TOKEN is not set: creating and deleting links is open to anyone!"
+        );
+    }
+
+    // Keep the temporary directory guard alive for the whole process so it
+    // isn't deleted from underneath the environment.
+    let (path, _tempdir) = data_dir()?;
+    info!("Using LMDB data directory {}", path.display());
 
-    // Create (or open) the LMDB environment in the "data.mdb" directory.
+    // Create (or open) the LMDB environment in the data directory.
     let env = Arc::new(unsafe {
         EnvOpenOptions::new()
-            .max_dbs(1) // Set the maximum number of databases
-            .open(path)?
+            .max_dbs(max_dbs) // Set the maximum number of databases
+            .map_size(map_size) // Writes fail with MDB_MAP_FULL beyond this
+            .open(&path)?
     });
 
     let mut wtxn = env.write_txn()?;
-    let db: Database<Str, Str> = env.create_database(&mut wtxn, Some("requests"))?;
-    wtxn.commit()?; // Commit the transaction after creating the database.
+    let db: LinkDb = env.create_database(&mut wtxn, Some("requests"))?;
+    let clicks: Database<Str, U64<BigEndian>> = env.create_database(&mut wtxn, Some("clicks"))?;
+    let namespaces = open_namespaces(&env, &wtxn)?;
+    wtxn.commit()?; // Commit the transaction after creating the databases.
+    info!("Opened {} namespaces", namespaces.len());
+
+    let writes = TaskTracker::new();
+    let (click_queue, queued_clicks) = mpsc::channel(CLICK_QUEUE_CAPACITY);
+    let app_state = AppState {
+        env: env.clone(),
+        db,
+        namespaces: Arc::new(RwLock::new(namespaces)),
+        clicks,
+        click_queue,
+        writes: writes.clone(),
+        aliases,
+        base_url,
+        trust_proxy: limiter.trust_proxy,
+        metrics,
+        batch_limiter: Arc::new(batch_limiter),
+    };
+    // Stops the background loops once the server has drained. The db writers
+    // are tracked so shutdown also waits for their last transactions.
+    let background = CancellationToken::new();
+    writes.spawn(sweep_expired(app_state.clone(), background.clone()));
+    writes.spawn(click_writer(
+        env.clone(),
+        clicks,
+        queued_clicks,
+        background.clone(),
+    ));
+    tokio::spawn(metrics_upkeep(
+        app_state.metrics.clone(),
+        background.clone(),
+    ));
 
-    let app_state = AppState { env, db };
+    // Only link creation is throttled; redirects stay unlimited.
+    let rate_limited = middleware::from_fn_with_state(limiter, rate_limit);
+    // Mutating routes need the admin token; reads stay public. Layered outside
+    // the rate limiter so unauthenticated requests don't spend tokens.
+    let admin_only = middleware::from_fn_with_state(admin_token, require_admin);
 
     let app = Router::new()
-        .route("/{alias}", get(root))
-        .route("/", post(shorten))
-        .layer(ServiceBuilder::new().layer(middleware::from_fn(logging_middleware)))
+        .route(
+            "/{alias}",
+            get(root).merge(delete(delete_alias).layer(admin_only.clone())),
+        )
+        .route(
+            "/",
+            get(index).merge(post(shorten).layer(rate_limited).layer(admin_only.clone())),
+        )
+        .route(
+            "/{namespace}/{alias}",
+            get(namespaced_root).merge(delete(namespaced_delete).layer(admin_only.clone())),
+        )
+        .route("/stats/{alias}", get(stats))
+        .route("/api/links", get(list_links))
+        .route("/api/links/batch", post(batch_shorten).layer(admin_only))
+        .route("/api/reverse", get(reverse_lookup))
+        .layer(
+            ServiceBuilder::new()
+                // Reuse a client-supplied `X-Request-Id` or mint a UUID, and
+                // echo it back on the response.
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(middleware::from_fn(logging_middleware)),
+        )
+        // Registered after the logging layer so probes don't flood the logs,
+        // and so scrapes aren't counted in the request metrics.
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(render_metrics))
         .with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("go.silly serving on {}....", listener.local_addr()?);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+
+    // In-flight requests have drained; stop the background loops and let
+    // detached writes (click counts, sweeps, writes from disconnected clients)
+    // finish before exiting.
+    background.cancel();
+    writes.close();
+    info!("Waiting for {} pending writes...", writes.len());
+    writes.wait().await;
+    env.force_sync()?;
+    info!("go.silly shut down cleanly");
     Ok(())
 }
 
+/// Installs the global subscriber, emitting JSON lines when
+/// `SILLY_LOG_FORMAT=json` and the human-readable format otherwise.
+fn init_tracing() -> Result<(), String> {
+    let builder = FmtSubscriber::builder().with_max_level(Level::INFO);
+
+    let result = match std::env::var("SILLY_LOG_FORMAT").as_deref() {
+        Ok("json") => tracing::subscriber::set_global_default(builder.json().finish()),
+        Ok("pretty") | Err(_) => tracing::subscriber::set_global_default(builder.finish()),
+        Ok(other) => {
+            return Err(format!(
+                "invalid SILLY_LOG_FORMAT {other}: expected json or pretty"
+            ))
+        }
+    };
+    result.map_err(|error| format!("setting default subscriber failed: {error}"))
+}
+
+/// Completes on the first of Ctrl-C (SIGINT) or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT, shutting down..."),
+        _ = terminate => info!("Received SIGTERM, shutting down..."),
+    }
+}
+
+/// Per-client token buckets guarding link creation.
+///
+/// Each IP starts with `per_minute` tokens and regains them continuously at
+/// the same rate; every link created spends one token.
+struct RateLimiter {
+    per_minute: u32,
+    /// Take the client IP from `X-Forwarded-For` rather than the socket.
+    trust_proxy: bool,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Reads the per-minute limit from `var` (e.g. `SILLY_RATE_LIMIT`) and
+    /// `SILLY_TRUST_PROXY` (`true`/`1` to honour `X-Forwarded-For`).
+    fn from_env(var: &str, default: u32) -> Result<Self, String> {
+        let per_minute = match std::env::var(var) {
+            Ok(raw) => raw
+                .parse()
+                .ok()
+                .filter(|&limit| limit > 0)
+                .ok_or(format!("invalid {var} {raw}: expected a positive integer"))?,
+            Err(_) => default,
+        };
+        let trust_proxy = std::env::var("SILLY_TRUST_PROXY")
+            .is_ok_and(|raw| matches!(raw.as_str(), "1" | "true"));
+
+        Ok(RateLimiter::new(per_minute, trust_proxy))
+    }
+
+    fn new(per_minute: u32, trust_proxy: bool) -> Self {
+        RateLimiter {
+            per_minute,
+            trust_proxy,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Spends `cost` tokens for `ip`, or returns how long until they're
+    /// available. `cost` must not exceed `per_minute`.
+    fn check(&self, ip: IpAddr, cost: u32) -> Result<(), Duration> {
+        self.check_at(ip, cost, Instant::now())
+    }
+
+    /// `check` as of `now`.
+    fn check_at(&self, ip: IpAddr, cost: u32, now: Instant) -> Result<(), Duration> {
+        let cost = f64::from(cost);
+        let capacity = f64::from(self.per_minute);
+        let refill_per_sec = capacity / 60.0;
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Forget clients whose buckets have refilled so the map can't grow
+        // without bound under a spray of source addresses.
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                bucket.tokens + elapsed * refill_per_sec < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (cost - bucket.tokens) / refill_per_sec,
+            ))
+        }
+    }
+
+    /// The client address, preferring the last `X-Forwarded-For` hop when
+    /// the proxy in front of us is trusted. Proxies append the address they
+    /// saw to the list, so only the last entry is out of the client's hands.
+    fn client_ip(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        let forwarded = self
+            .trust_proxy
+            .then(|| headers.get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|last| last.trim().parse().ok());
+        forwarded.unwrap_or(peer.ip())
+    }
+}
+
+async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let ip = limiter.client_ip(req.headers(), peer);
+
+    match limiter.check(ip, 1) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => rate_limited_response(ip, retry_after),
+    }
+}
+
+/// A `429` telling the client to back off for `retry_after`.
+fn rate_limited_response(ip: IpAddr, retry_after: Duration) -> Response {
+    tracing::warn!("Rate limited {}....", ip);
+    let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded");
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after_secs(retry_after)),
+    );
+    response
+}
+
+/// Whole seconds for `Retry-After`, rounded up so clients never retry early.
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+/// Requires `Authorization: Bearer <SILLY_ADMIN_TOKEN>`, answering `401` when
+/// the header is missing or malformed and `403` when the token is wrong. With
+/// no token configured every request is let through.
+async fn require_admin(
+    State(token): State<Option<Arc<str>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = token else {
+        return next.run(req).await;
+    };
+
+    let presented = req.headers().get(header::AUTHORIZATION).map(bearer_token);
+
+    let message = match presented {
+        // Constant-time so the comparison doesn't leak how much of the token matched.
+        Some(Some(presented)) if bool::from(presented.as_bytes().ct_eq(token.as_bytes())) => {
+            return next.run(req).await;
+        }
+        Some(Some(_)) => {
+            tracing::warn!("Rejected request with an invalid admin token....");
+            return error_response(StatusCode::FORBIDDEN, "invalid admin token");
+        }
+        Some(None) => "malformed authorization header",
+        None => "missing admin token",
+    };
+    let mut response = error_response(StatusCode::UNAUTHORIZED, message);
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// The token of a `Bearer <token>` credential. Auth schemes are
+/// case-insensitive (RFC 7235), so `bearer` is accepted too.
+fn bearer_token(value: &HeaderValue) -> Option<&str> {
+    let (scheme, token) = value.to_str().ok()?.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Reads `SILLY_BASE_URL` (e.g. `https://sil.ly`), the prefix for returned
+/// short links.
+fn configured_base_url() -> Result<Option<Url>, String> {
+    let Some(raw) = std::env::var("SILLY_BASE_URL")
+        .ok()
+        .filter(|raw| !raw.is_empty())
+    else {
+        return Ok(None);
+    };
+    match validate_url(&raw) {
+        Ok(mut base) => {
+            base.set_query(None);
+            base.set_fragment(None);
+            Ok(Some(base))
+        }
+        Err(error) => Err(format!("invalid SILLY_BASE_URL {raw}: {error}")),
+    }
+}
+
+/// The prefix for short links: `SILLY_BASE_URL` when configured, otherwise
+/// derived from the request by `request_base_url`.
+fn base_url(state: &AppState, headers: &HeaderMap, uri: &Uri) -> Option<Url> {
+    match &state.base_url {
+        Some(base) => Some(base.clone()),
+        None => request_base_url(headers, uri, state.trust_proxy),
+    }
+}
+
+/// The request's `Host` (or HTTP/2 `:authority`) with the scheme from
+/// `X-Forwarded-Proto` when the proxy is trusted, else the request's own
+/// (default `http`).
+fn request_base_url(headers: &HeaderMap, uri: &Uri, trust_proxy: bool) -> Option<Url> {
+    let host = match headers.get(header::HOST) {
+        Some(host) => host.to_str().ok()?,
+        None => uri.authority()?.as_str(),
+    };
+    let forwarded_proto = trust_proxy
+        .then(|| headers.get("x-forwarded-proto"))
+        .flatten()
+        .map(HeaderValue::to_str);
+    let scheme = match forwarded_proto {
+        Some(Ok("https")) => "https",
+        Some(_) => "http",
+        None => uri.scheme_str().unwrap_or("http"),
+    };
+    Url::parse(&format!("{scheme}://{host}")).ok()
+}
+
+/// Appends the namespace (if any) and `alias` as path segments of `base`, so
+/// bases with or without a trailing slash never produce `//`.
+fn join_short_url(base: &Url, namespace: Option<&str>, alias: &str) -> String {
+    let mut url = base.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().extend(namespace).push(alias);
+    }
+    url.into()
+}
+
+impl AppState {
+    /// The links db for `namespace`, or the default `requests` db for `None`.
+    /// Namespaces nobody has written to yet have no db and return `None`.
+    fn links_db(&self, namespace: Option<&str>) -> Option<LinkDb> {
+        match namespace {
+            None => Some(self.db),
+            Some(namespace) => self
+                .namespaces
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(namespace)
+                .copied(),
+        }
+    }
+
+    /// Like `links_db`, but creates the namespace's db inside `wtxn` on first
+    /// use. Rejected once every LMDB database slot is taken.
+    ///
+    /// A db created here vanishes if `wtxn` is aborted, so it's only cached
+    /// by `cache_links_db` once the caller has committed.
+    fn create_links_db(
+        &self,
+        wtxn: &mut RwTxn,
+        namespace: Option<&str>,
+    ) -> heed::Result<Result<LinkDb, Rejection>> {
+        let Some(namespace) = namespace else {
+            return Ok(Ok(self.db));
+        };
+        if let Some(db) = self.links_db(Some(namespace)) {
+            return Ok(Ok(db));
+        }
+
+        // LMDB serialises writers, so a racing first write to the same
+        // namespace has either committed its db by now or not started.
+        let name = format!("{NAMESPACE_DB_PREFIX}{namespace}");
+        let db = match self.env.open_database(wtxn, Some(&name)) {
+            Ok(Some(db)) => Ok(db),
+            Ok(None) => self.env.create_database(wtxn, Some(&name)),
+            Err(error) => Err(error),
+        };
+        // Opening needs a free slot as well, so either call can run out.
+        match db {
+            Ok(db) => Ok(Ok(db)),
+            Err(heed::Error::Mdb(MdbError::DbsFull)) => Ok(Err((
+                StatusCode::INSUFFICIENT_STORAGE,
+                "namespace limit reached",
+            ))),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Remembers a db returned by `create_links_db` once its transaction has
+    /// committed. The write lock is only taken for namespaces not seen yet.
+    fn cache_links_db(&self, namespace: Option<&str>, db: LinkDb) {
+        let Some(namespace) = namespace else {
+            return;
+        };
+        if self.links_db(Some(namespace)).is_some() {
+            return;
+        }
+        let mut namespaces = self
+            .namespaces
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if namespaces.insert(namespace.to_string(), db).is_none() {
+            info!("Created namespace {}...", namespace);
+        }
+    }
+
+    /// Every links db paired with its namespace, default first.
+    fn all_links_dbs(&self) -> Vec<(Option<String>, LinkDb)> {
+        let namespaces = self
+            .namespaces
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        std::iter::once((None, self.db))
+            .chain(
+                namespaces
+                    .iter()
+                    .map(|(name, db)| (Some(name.clone()), *db)),
+            )
+            .collect()
+    }
+}
+
+/// Opens the db of every namespace created by a previous run, found by
+/// scanning the names of LMDB's unnamed main database.
+fn open_namespaces(env: &Env, txn: &RoTxn) -> heed::Result<HashMap<String, LinkDb>> {
+    let Some(main) = env.open_database::<Str, DecodeIgnore>(txn, None)? else {
+        return Ok(HashMap::new());
+    };
+    let names = main
+        .prefix_iter(txn, NAMESPACE_DB_PREFIX)?
+        .map(|entry| entry.map(|(name, ())| name.to_string()))
+        .collect::<heed::Result<Vec<String>>>()?;
+
+    let mut namespaces = HashMap::with_capacity(names.len());
+    for name in names {
+        if let Some(db) = env.open_database(txn, Some(&name))? {
+            namespaces.insert(name[NAMESPACE_DB_PREFIX.len()..].to_string(), db);
+        }
+    }
+    Ok(namespaces)
+}
+
+/// Key of an alias in the shared `clicks` db. Aliases can't contain `/`, so
+/// `namespace/alias` never collides with a default-namespace alias.
+fn click_key(namespace: Option<&str>, alias: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{namespace}/{alias}"),
+        None => alias.to_string(),
+    }
+}
+
+/// Reads `SILLY_MAX_DBS`, the cap on LMDB databases (and so namespaces).
+fn max_dbs() -> Result<u32, String> {
+    match std::env::var("SILLY_MAX_DBS") {
+        Ok(raw) => raw.parse().ok().filter(|&max| max >= 2).ok_or(format!(
+            "invalid SILLY_MAX_DBS {raw}: expected an integer of at least 2"
+        )),
+        Err(_) => Ok(DEFAULT_MAX_DBS),
+    }
+}
+
+/// Reads `SILLY_MAP_SIZE`, the most bytes the LMDB environment may grow to.
+/// It must be a multiple of the OS page size.
+fn map_size() -> Result<usize, String> {
+    match std::env::var("SILLY_MAP_SIZE") {
+        Ok(raw) => raw.parse().ok().filter(|&size| size > 0).ok_or(format!(
+            "invalid SILLY_MAP_SIZE {raw}: expected a positive number of bytes"
+        )),
+        Err(_) => Ok(DEFAULT_MAP_SIZE),
+    }
+}
+
+/// Resolves the address to listen on.
+///
+/// `SILLY_BIND_ADDR` (e.g. `127.0.0.1:8080`) replaces the `0.0.0.0:3000`
+/// default, and a `--port <PORT>` flag overrides the port of either.
+fn bind_addr() -> Result<SocketAddr, String> {
+    let raw = std::env::var("SILLY_BIND_ADDR").unwrap_or_else(|_| DEFAULT_BIND_ADDR.to_string());
+    let mut addr: SocketAddr = raw
+        .parse()
+        .map_err(|error| format!("invalid SILLY_BIND_ADDR {raw}: {error}"))?;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let port = if arg == "--port" {
+            args.next().ok_or("--port requires a value")?
+        } else if let Some(value) = arg.strip_prefix("--port=") {
+            value.to_string()
+        } else {
+            continue;
+        };
+        let port = port
+            .parse()
+            .map_err(|error| format!("invalid --port {port}: {error}"))?;
+        addr.set_port(port);
+    }
+
+    Ok(addr)
+}
+
+/// Resolves the directory backing the LMDB environment.
+///
+/// `SILLY_DATA_DIR` takes precedence, falling back to `./data`. Only when the
+/// variable is unset and `--ephemeral` is passed is a temporary directory used
+/// instead; its guard is returned so the caller can keep it alive.
+fn data_dir() -> std::io::Result<(PathBuf, Option<TempDir>)> {
+    let ephemeral = std::env::args().skip(1).any(|arg| arg == "--ephemeral");
+
+    match std::env::var_os("SILLY_DATA_DIR") {
+        None if ephemeral => {
+            let tempdir = tempfile::tempdir()?;
+            Ok((tempdir.path().to_path_buf(), Some(tempdir)))
+        }
+        dir => {
+            let path = dir.map_or_else(|| PathBuf::from(DEFAULT_DATA_DIR), PathBuf::from);
+            std::fs::create_dir_all(&path)?;
+            Ok((path, None))
+        }
+    }
+}
+
 async fn logging_middleware(req: Request, next: Next) -> Response {
     let start = Instant::now();
 
     let method = req.method().clone();
     let uri = req.uri().clone();
-    info!("Incoming request: {} {}", method, uri);
+    // Set by `SetRequestIdLayer`, so every request has one by the time we run.
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let span = tracing::info_span!("request", %request_id);
 
-    let response: Response<Body> = next.run(req).await;
+    async move {
+        info!(%method, %uri, "Incoming request");
 
-    let latency = start.elapsed();
-    info!("Processed request: {} {} in {:?}", method, uri, latency);
+        let response: Response<Body> = next.run(req).await;
 
-    response
+        let latency = start.elapsed();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        info!(%method, %uri, latency_ms, "Processed request");
+        metrics::histogram!(
+            "silly_request_duration_seconds",
+            "method" => method_label(&method),
+            "status" => response.status().as_str().to_owned(),
+        )
+        .record(latency.as_secs_f64());
+
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+/// The `method` label for request metrics. Anything outside the standard
+/// methods becomes `other`, so clients can't mint a new series per request.
+fn method_label(method: &Method) -> &'static str {
+    const STANDARD: [(Method, &str); 9] = [
+        (Method::GET, "GET"),
+        (Method::HEAD, "HEAD"),
+        (Method::POST, "POST"),
+        (Method::PUT, "PUT"),
+        (Method::DELETE, "DELETE"),
+        (Method::CONNECT, "CONNECT"),
+        (Method::OPTIONS, "OPTIONS"),
+        (Method::TRACE, "TRACE"),
+        (Method::PATCH, "PATCH"),
+    ];
+    STANDARD
+        .iter()
+        .find(|(standard, _)| standard == method)
+        .map_or("other", |&(_, label)| label)
+}
+
+/// Serves a self-contained form that creates links through `POST /`.
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+/// The homepage: a form that submits to `shorten()` as JSON via `fetch`.
+const INDEX_HTML: &str = r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>go.silly</title>
+<style>
+  body { font-family: system-ui, sans-serif; max-width: 32rem; margin: 4rem auto; padding: 0 1rem; }
+  label { display: block; margin-top: 1rem; }
+  input { box-sizing: border-box; width: 100%; padding: 0.5rem; }
+  button { margin-top: 1rem; padding: 0.5rem 1rem; }
+  #result { margin-top: 1.5rem; }
+  .error { color: #b00020; }
+</style>
+</head>
+<body>
+<h1>go.silly</h1>
+<form id="shorten">
+  <label>URL <input name="url" type="url" required placeholder="https://example.com"></label>
+  <label>Alias (optional) <input name="alias" placeholder="generated if empty"></label>
+  <label>Admin token (if required) <input name="token" type="password" autocomplete="off"></label>
+  <button type="submit">Shorten</button>
+</form>
+<p id="result"></p>
+<script>
+  const form = document.getElementById("shorten");
+  const result = document.getElementById("result");
+
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    const data = new FormData(form);
+    const payload = { url: data.get("url") };
+    if (data.get("alias")) payload.alias = data.get("alias");
+    const headers = { "Content-Type": "application/json" };
+    if (data.get("token")) headers.Authorization = "Bearer " + data.get("token");
+
+    result.replaceChildren();
+    result.className = "";
+    try {
+      const response = await fetch("/", { method: "POST", headers, body: JSON.stringify(payload) });
+      const body = await response.json().catch(() => ({ error: response.statusText }));
+      if (!response.ok) throw new Error(body.error || response.statusText);
+      const href = body.short_url || location.origin + "/" + encodeURIComponent(body.alias);
+      const link = document.createElement("a");
+      link.href = href;
+      link.textContent = href;
+      result.append("Short link: ", link);
+    } catch (error) {
+      result.className = "error";
+      result.textContent = error.message;
+    }
+  });
+</script>
+</body>
+</html>
+"#;
+
+async fn root(
+    State(state): State<AppState>,
+    Path(alias): Path<String>,
+) -> Result<Response, AppError> {
+    resolve(&state, None, &alias)
 }
 
-async fn root(State(state): State<AppState>, Path(alias): Path<String>) -> impl IntoResponse {
+async fn namespaced_root(
+    State(state): State<AppState>,
+    Path((namespace, alias)): Path<(String, String)>,
+) -> Result<Response, AppError> {
+    match normalize_namespace(&namespace, &state.aliases) {
+        Ok(namespace) => resolve(&state, Some(&namespace), &alias),
+        Err(message) => Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    }
+}
+
+/// Redirects to the destination of `alias` within `namespace`.
+fn resolve(state: &AppState, namespace: Option<&str>, alias: &str) -> Result<Response, AppError> {
+    let alias = match normalize_alias(alias, &state.aliases) {
+        Ok(alias) => alias,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    let Some(db) = state.links_db(namespace) else {
+        tracing::warn!("Namespace {:?} not found....", namespace);
+        metrics::counter!("silly_not_found_total").increment(1);
+        return Ok(error_response(StatusCode::NOT_FOUND, "alias not found"));
+    };
     let env = state.env.clone();
-    let db = state.db;
-    let rtxn = env.read_txn().unwrap();
+    let rtxn = env.read_txn()?;
 
-    match db.get(&rtxn, &alias) {
-        Ok(Some(value)) => {
-            info!("Redirecting {} to {}...", &alias, value);
-            Redirect::temporary(value)
+    let response = match db.get(&rtxn, &alias)? {
+        Some(record) if record.is_expired(unix_now()) => {
+            tracing::warn!("Short link {} has expired....", &alias);
+            error_response(StatusCode::GONE, "alias expired")
+        }
+        Some(record) => {
+            info!("Redirecting {} to {}...", &alias, record.url);
+            metrics::counter!("silly_redirects_total").increment(1);
+            record_click(state, click_key(namespace, &alias));
+            if record.permanent {
+                // `Redirect::permanent` is a 308; SEO tooling expects a 301.
+                let location = [(header::LOCATION, record.url)];
+                (StatusCode::MOVED_PERMANENTLY, location).into_response()
+            } else {
+                Redirect::temporary(&record.url).into_response()
+            }
         }
-        Ok(None) => {
+        None => {
             tracing::warn!("Short link {} not found....", &alias);
-            Redirect::temporary("/")
+            metrics::counter!("silly_not_found_total").increment(1);
+            error_response(StatusCode::NOT_FOUND, "alias not found")
+        }
+    };
+    Ok(response)
+}
+
+/// Queues a click under `key` for `click_writer` without holding up the
+/// redirect. Clicks are dropped rather than queued without bound under load.
+fn record_click(state: &AppState, key: String) {
+    match state.click_queue.try_send(key) {
+        Ok(()) => {}
+        Err(TrySendError::Full(key)) => {
+            tracing::warn!("Click queue full, dropping click for {}....", key);
         }
+        Err(TrySendError::Closed(key)) => {
+            tracing::warn!("Click writer stopped, dropping click for {}....", key);
+        }
+    }
+}
+
+/// Sums queued clicks and commits them once per `CLICK_FLUSH_INTERVAL`, so a
+/// burst of redirects costs one write transaction rather than one each. On
+/// `stop` it counts whatever is still queued and commits a final time.
+async fn click_writer(
+    env: Arc<Env>,
+    clicks: Database<Str, U64<BigEndian>>,
+    mut queue: mpsc::Receiver<String>,
+    stop: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(CLICK_FLUSH_INTERVAL);
+    let mut pending: HashMap<String, u64> = HashMap::new();
+    loop {
+        tokio::select! {
+            Some(key) = queue.recv() => *pending.entry(key).or_default() += 1,
+            _ = interval.tick() => flush_clicks(&env, clicks, &mut pending).await,
+            () = stop.cancelled() => break,
+        }
+    }
+
+    queue.close();
+    while let Ok(key) = queue.try_recv() {
+        *pending.entry(key).or_default() += 1;
+    }
+    flush_clicks(&env, clicks, &mut pending).await;
+}
+
+/// Commits and clears `pending` on a blocking thread. Counts that fail to
+/// commit are logged and dropped.
+async fn flush_clicks(
+    env: &Arc<Env>,
+    clicks: Database<Str, U64<BigEndian>>,
+    pending: &mut HashMap<String, u64>,
+) {
+    if pending.is_empty() {
+        return;
+    }
+    let env = env.clone();
+    let counts = std::mem::take(pending);
+    let added = tokio::task::spawn_blocking(move || add_clicks(&env, clicks, &counts)).await;
+
+    match added {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => tracing::error!("Failed to record clicks: {}", error),
+        Err(error) => tracing::error!("Click flush panicked: {}", error),
+    }
+}
+
+/// Adds every count inside a single write transaction. LMDB allows only one
+/// writer at a time, so concurrent writes to the same key never lose counts.
+fn add_clicks(
+    env: &Env,
+    clicks: Database<Str, U64<BigEndian>>,
+    counts: &HashMap<String, u64>,
+) -> heed::Result<()> {
+    let mut wtxn = env.write_txn()?;
+    for (key, added) in counts {
+        let count = clicks.get(&wtxn, key)?.unwrap_or(0);
+        clicks.put(&mut wtxn, key, &(count + added))?;
+    }
+    wtxn.commit()
+}
+
+async fn stats(
+    State(state): State<AppState>,
+    Path(alias): Path<String>,
+    Query(scope): Query<NamespaceQuery>,
+) -> Result<Response, AppError> {
+    let alias = match normalize_alias(&alias, &state.aliases) {
+        Ok(alias) => alias,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    let namespace = match scope.normalize(&state.aliases) {
+        Ok(namespace) => namespace,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    let Some(db) = state.links_db(namespace.as_deref()) else {
+        tracing::warn!("Namespace {:?} not found....", namespace);
+        return Ok(error_response(StatusCode::NOT_FOUND, "alias not found"));
+    };
+    let env = state.env.clone();
+    let rtxn = env.read_txn()?;
+
+    let Some(record) = db.get(&rtxn, &alias)? else {
+        tracing::warn!("Short link {} not found....", &alias);
+        return Ok(error_response(StatusCode::NOT_FOUND, "alias not found"));
+    };
+    if record.is_expired(unix_now()) {
+        tracing::warn!("Short link {} has expired....", &alias);
+        return Ok(error_response(StatusCode::GONE, "alias expired"));
+    }
+    let key = click_key(namespace.as_deref(), &alias);
+    let stats = LinkStats {
+        clicks: state.clicks.get(&rtxn, &key)?.unwrap_or(0),
+        alias,
+        url: record.url,
+    };
+    Ok(Json(stats).into_response())
+}
+
+async fn list_links(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(scope): Query<NamespaceQuery>,
+    Query(page): Query<Pagination>,
+) -> Result<Response, AppError> {
+    let namespace = match scope.normalize(&state.aliases) {
+        Ok(namespace) => namespace,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    // A namespace nobody has written to has no db, and so no links.
+    let Some(db) = state.links_db(namespace.as_deref()) else {
+        return Ok(Json(Vec::<ShortUrl>::new()).into_response());
+    };
+    let env = state.env.clone();
+
+    // Collect within a plain block so the read transaction is dropped before
+    // the handler could ever reach an await point.
+    let now = unix_now();
+    let links = {
+        let rtxn = env.read_txn()?;
+        let links: heed::Result<Vec<ShortUrl>> = db.iter(&rtxn).and_then(|iter| {
+            // Expired links linger until the next sweep; don't list them.
+            iter.filter(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(_, record)| !record.is_expired(now))
+            })
+            .skip(page.offset)
+            .take(page.limit)
+            .map(|entry| {
+                entry.map(|(alias, record)| ShortUrl::stored(namespace.as_deref(), alias, record))
+            })
+            .collect()
+        });
+        let mut links = links?;
+        let base = base_url(&state, &headers, &uri);
+        for link in &mut links {
+            link.set_short_url(base.as_ref());
+        }
+        links
+    };
+
+    Ok(Json(links).into_response())
+}
+
+/// Lists every alias in `?namespace=` (default: the default namespace)
+/// pointing at `?url=`.
+///
+/// Values aren't indexed, so this scans the whole namespace db and costs O(n)
+/// in the number of stored links regardless of how many match; `?limit=` and
+/// `?offset=` only bound the size of the response.
+async fn reverse_lookup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(query): Query<ReverseQuery>,
+    Query(scope): Query<NamespaceQuery>,
+    Query(page): Query<Pagination>,
+) -> Result<Response, AppError> {
+    let namespace = match scope.normalize(&state.aliases) {
+        Ok(namespace) => namespace,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    let Some(db) = state.links_db(namespace.as_deref()) else {
+        return Ok(Json(Vec::<ShortUrl>::new()).into_response());
+    };
+    let env = state.env.clone();
+    // Stored URLs are normalised by `validate_url`, so compare like with like.
+    let target = validate_url(&query.url).map_or(query.url, String::from);
+    let now = unix_now();
+
+    let links = {
+        let rtxn = env.read_txn()?;
+        let links: heed::Result<Vec<ShortUrl>> = db.iter(&rtxn).and_then(|iter| {
+            iter.filter(|entry| {
+                entry.as_ref().map_or(true, |(_, record)| {
+                    record.url == target && !record.is_expired(now)
+                })
+            })
+            .skip(page.offset)
+            .take(page.limit)
+            .map(|entry| {
+                entry.map(|(alias, record)| ShortUrl::stored(namespace.as_deref(), alias, record))
+            })
+            .collect()
+        });
+        let mut links = links?;
+        let base = base_url(&state, &headers, &uri);
+        for link in &mut links {
+            link.set_short_url(base.as_ref());
+        }
+        links
+    };
+
+    Ok(Json(links).into_response())
+}
+
+/// Reports healthy only once the LMDB environment answers a read transaction.
+async fn healthz(State(state): State<AppState>) -> Response {
+    let readable = state.env.read_txn().and_then(|rtxn| state.db.len(&rtxn));
+
+    match readable {
+        Ok(_) => Json(Health { status: "ok" }).into_response(),
         Err(error) => {
-            tracing::error!("Database read error: {}", error);
-            Redirect::temporary("/")
+            tracing::error!("Health check failed: {}", error);
+            let health = Health {
+                status: "unavailable",
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(health)).into_response()
         }
     }
 }
 
+/// A client-facing refusal to create a link, reported in `ShortUrl.error`.
+type Rejection = (StatusCode, &'static str);
+
+/// Internal failures surfaced by handlers as a JSON `500`.
+enum AppError {
+    /// An LMDB operation failed.
+    Database(heed::Error),
+    /// A blocking db task panicked or was cancelled.
+    Task(JoinError),
+}
+
+impl From<heed::Error> for AppError {
+    fn from(error: heed::Error) -> Self {
+        AppError::Database(error)
+    }
+}
+
+impl From<JoinError> for AppError {
+    fn from(error: JoinError) -> Self {
+        AppError::Task(error)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            AppError::Database(error) => {
+                tracing::error!("Database error: {}", error);
+                "database error"
+            }
+            AppError::Task(error) => {
+                tracing::error!("Blocking task failed: {}", error);
+                "internal error"
+            }
+        };
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+/// Exposes the collected metrics in the Prometheus text format.
+async fn render_metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
+
+/// Without the exporter's own HTTP listener nobody else drains histogram
+/// buffers, so do it here periodically.
+async fn metrics_upkeep(handle: PrometheusHandle, stop: CancellationToken) {
+    let mut interval = tokio::time::interval(METRICS_UPKEEP_INTERVAL);
+    while stop.run_until_cancelled(interval.tick()).await.is_some() {
+        handle.run_upkeep();
+    }
+}
+
+/// Builds a JSON `{"error": ...}` response with the given status.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let body = ErrorBody {
+        error: message.to_string(),
+    };
+    (status, Json(body)).into_response()
+}
+
 async fn shorten(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
     Json(payload): Json<CreateShortUrl>,
-) -> (StatusCode, Json<ShortUrl>) {
-    let url = ShortUrl {
+) -> Result<(StatusCode, Json<ShortUrl>), AppError> {
+    let (mut url, requested) = match prepare_link(payload, &state.aliases) {
+        Ok(prepared) => prepared,
+        Err(url) => return Ok((StatusCode::BAD_REQUEST, Json(*url))),
+    };
+
+    // Offload the blocking LMDB write to a blocking thread.
+    let blocking_state = state.clone();
+    let namespace = url.namespace.clone();
+    let val = url.record();
+
+    let inserted = state
+        .writes
+        .spawn_blocking(move || -> heed::Result<Result<String, Rejection>> {
+            let state = blocking_state;
+            // Begin a write transaction.
+            let mut wtxn = state.env.write_txn()?;
+            let db = match state.create_links_db(&mut wtxn, namespace.as_deref())? {
+                Ok(db) => db,
+                Err(rejection) => return Ok(Err(rejection)),
+            };
+            let inserted = insert_link(
+                db,
+                state.clicks,
+                &mut wtxn,
+                namespace.as_deref(),
+                requested,
+                &val,
+                &state.aliases,
+            )?;
+            // Commit the transaction; on rejection dropping `wtxn` aborts it,
+            // along with any namespace db it created.
+            if inserted.is_ok() {
+                wtxn.commit()?;
+                state.cache_links_db(namespace.as_deref(), db);
+            }
+            Ok(inserted)
+        })
+        .await??;
+
+    match inserted {
+        Ok(alias) => {
+            metrics::counter!("silly_shorten_total").increment(1);
+            url.alias = alias;
+            url.set_short_url(base_url(&state, &headers, &uri).as_ref());
+            Ok((StatusCode::CREATED, Json(url)))
+        }
+        Err((status, message)) => {
+            tracing::warn!("Failed to shorten {}: {}....", &url.url, message);
+            url.error = Some(message.to_string());
+            Ok((status, Json(url)))
+        }
+    }
+}
+
+/// Creates many links in a single write transaction.
+///
+/// Entries that fail validation or conflict are reported through their own
+/// `error` field without affecting the rest; only a failed commit rolls back
+/// the whole batch, including any namespaces it created, and returns a `500`.
+///
+/// Rate limited separately from `POST /`, to `SILLY_BATCH_RATE_LIMIT` links
+/// per minute per IP with one token charged per entry. Batches that could
+/// never fit in a bucket are refused outright.
+async fn batch_shorten(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    uri: Uri,
+    Json(payloads): Json<Vec<CreateShortUrl>>,
+) -> Result<Response, AppError> {
+    let limiter = &state.batch_limiter;
+    let max_batch = MAX_BATCH_SIZE.min(limiter.per_minute as usize);
+    if payloads.len() > max_batch {
+        let message = format!("batch must contain at most {max_batch} links");
+        return Ok(error_response(StatusCode::PAYLOAD_TOO_LARGE, &message));
+    }
+    let ip = limiter.client_ip(&headers, peer);
+    // Bounded by `max_batch` above, so it fits in a bucket.
+    let cost = payloads.len() as u32;
+    if let Err(retry_after) = limiter.check(ip, cost) {
+        return Ok(rate_limited_response(ip, retry_after));
+    }
+
+    let prepared: Vec<_> = payloads
+        .into_iter()
+        .map(|payload| prepare_link(payload, &state.aliases))
+        .collect();
+
+    // Offload the blocking LMDB write to a blocking thread.
+    let blocking_state = state.clone();
+
+    let mut urls = state
+        .writes
+        .spawn_blocking(move || -> heed::Result<Vec<ShortUrl>> {
+            let state = blocking_state;
+            // Begin a write transaction. Namespace dbs are created inside it
+            // too, so a failed commit leaves none of them behind.
+            let mut wtxn = state.env.write_txn()?;
+            let (urls, dbs) = insert_batch(&state, &mut wtxn, prepared)?;
+            // Commit the transaction.
+            wtxn.commit()?;
+            for (namespace, db) in dbs {
+                state.cache_links_db(namespace.as_deref(), db);
+            }
+            Ok(urls)
+        })
+        .await??;
+
+    // Only count links once the commit has made them real.
+    let created = urls.iter().filter(|url| url.error.is_none()).count();
+    metrics::counter!("silly_shorten_total").increment(created as u64);
+
+    let base = base_url(&state, &headers, &uri);
+    for url in &mut urls {
+        url.set_short_url(base.as_ref());
+    }
+    Ok(Json(urls).into_response())
+}
+
+/// Inserts every entry `prepare_link` accepted within the caller's
+/// transaction, recording rejections in each entry's `error`. Also returns the
+/// namespace dbs written to, for `cache_links_db` once `wtxn` commits.
+fn insert_batch(
+    state: &AppState,
+    wtxn: &mut RwTxn,
+    prepared: Vec<PreparedLink>,
+) -> heed::Result<(Vec<ShortUrl>, LinkDbs)> {
+    let mut dbs = HashMap::new();
+    let mut urls = Vec::with_capacity(prepared.len());
+    for entry in prepared {
+        let url = match entry {
+            Ok((mut url, requested)) => {
+                let db = match dbs.get(&url.namespace) {
+                    Some(&db) => Ok(db),
+                    None => state.create_links_db(wtxn, url.namespace.as_deref())?,
+                };
+                let inserted = match db {
+                    Ok(db) => {
+                        dbs.insert(url.namespace.clone(), db);
+                        insert_link(
+                            db,
+                            state.clicks,
+                            wtxn,
+                            url.namespace.as_deref(),
+                            requested,
+                            &url.record(),
+                            &state.aliases,
+                        )?
+                    }
+                    Err(rejection) => Err(rejection),
+                };
+                match inserted {
+                    Ok(alias) => url.alias = alias,
+                    Err((_, message)) => url.error = Some(message.to_string()),
+                }
+                url
+            }
+            Err(url) => *url,
+        };
+        urls.push(url);
+    }
+    Ok((urls, dbs))
+}
+
+/// Turns a create request into its response skeleton and requested alias,
+/// normalising the URL and alias. Rejected requests come back with `error` set.
+fn prepare_link(payload: CreateShortUrl, rules: &AliasRules) -> PreparedLink {
+    let mut url = ShortUrl {
         url: payload.url,
-        alias: payload.alias,
+        alias: payload.alias.clone().unwrap_or_default(),
+        permanent: payload.permanent,
+        expires_at: payload
+            .expires_in_secs
+            .map(|secs| unix_now().saturating_add(secs)),
+        namespace: payload.namespace.clone(),
+        short_url: None,
         error: None,
     };
 
+    if payload.expires_in_secs == Some(0) {
+        url.error = Some("expires_in_secs must be positive".to_string());
+        return Err(Box::new(url));
+    }
+
+    // Reject anything that isn't an absolute http(s) URL before touching the db.
+    match validate_url(&url.url) {
+        Ok(parsed) => url.url = parsed.into(),
+        Err(message) => {
+            tracing::warn!("Rejected URL {}: {}....", &url.url, message);
+            url.error = Some(message);
+            return Err(Box::new(url));
+        }
+    }
+
+    let requested = match payload
+        .alias
+        .as_deref()
+        .map(|raw| normalize_alias(raw, rules))
+    {
+        Some(Ok(alias)) => Some(alias),
+        Some(Err(message)) => {
+            tracing::warn!("Rejected alias {}: {}....", &url.alias, message);
+            url.error = Some(message);
+            return Err(Box::new(url));
+        }
+        None => None,
+    };
+    if let Some(alias) = &requested {
+        url.alias.clone_from(alias);
+    }
+
+    if let Some(raw) = payload.namespace.as_deref() {
+        match normalize_namespace(raw, rules) {
+            Ok(namespace) => url.namespace = Some(namespace),
+            Err(message) => {
+                tracing::warn!("Rejected namespace {}: {}....", raw, message);
+                url.error = Some(message);
+                return Err(Box::new(url));
+            }
+        }
+    }
+
+    Ok((url, requested))
+}
+
+/// Stores `record` under the requested alias, or a generated one when `None`.
+///
+/// The alias is checked within the caller's transaction so concurrent creates
+/// can't both claim it. Expired links that haven't been swept yet don't count
+/// as taken and are replaced. Nothing is written when the create is rejected.
+fn insert_link(
+    db: LinkDb,
+    clicks: Database<Str, U64<BigEndian>>,
+    wtxn: &mut RwTxn,
+    namespace: Option<&str>,
+    requested: Option<String>,
+    record: &LinkRecord,
+    rules: &AliasRules,
+) -> heed::Result<Result<String, Rejection>> {
+    let now = unix_now();
+    let key = match requested {
+        Some(alias)
+            if db
+                .get(wtxn, &alias)?
+                .is_some_and(|taken| !taken.is_expired(now)) =>
+        {
+            return Ok(Err((StatusCode::CONFLICT, "alias already in use")));
+        }
+        Some(alias) => alias,
+        None => match generate_free_alias(db, wtxn, rules, now)? {
+            Some(alias) => alias,
+            None => {
+                return Ok(Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to generate a unique alias",
+                )));
+            }
+        },
+    };
+    // Insert the key-value pair.
+    db.put(wtxn, key.as_str(), record)?;
+    // Any count left by an expired link under this alias isn't ours.
+    clicks.delete(wtxn, &click_key(namespace, &key))?;
+    Ok(Ok(key))
+}
+
+/// Periodically removes expired links (and their click counts) from every
+/// namespace until `stop` is cancelled.
+/// Links without an expiry are never touched.
+async fn sweep_expired(state: AppState, stop: CancellationToken) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    while stop.run_until_cancelled(interval.tick()).await.is_some() {
+        let env = state.env.clone();
+        // Snapshot up front so the namespace lock isn't held across the scan;
+        // namespaces created meanwhile are swept next time.
+        let dbs = state.all_links_dbs();
+        let clicks = state.clicks;
+        let swept = state
+            .writes
+            .spawn_blocking(move || -> heed::Result<usize> {
+                let mut wtxn = env.write_txn()?;
+                let swept = sweep_links(&mut wtxn, &dbs, clicks, unix_now())?;
+                wtxn.commit()?;
+                Ok(swept)
+            })
+            .await;
+
+        match swept {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => info!("Swept {} expired short links...", count),
+            Ok(Err(error)) => tracing::error!("Failed to sweep expired links: {}", error),
+            Err(error) => tracing::error!("Sweep task panicked: {}", error),
+        }
+    }
+}
+
+/// Deletes every link in `dbs` expired as of `now`, with its click count,
+/// inside the caller's transaction. Returns how many were removed.
+fn sweep_links(
+    wtxn: &mut RwTxn,
+    dbs: &[(Option<String>, LinkDb)],
+    clicks: Database<Str, U64<BigEndian>>,
+    now: u64,
+) -> heed::Result<usize> {
+    let mut swept = 0;
+    for (namespace, db) in dbs {
+        let expired = db
+            .iter(wtxn)?
+            .filter_map(|entry| match entry {
+                Ok((alias, record)) if record.is_expired(now) => Some(Ok(alias.to_string())),
+                Ok(_) => None,
+                Err(error) => Some(Err(error)),
+            })
+            .collect::<heed::Result<Vec<String>>>()?;
+        for alias in &expired {
+            db.delete(wtxn, alias)?;
+            clicks.delete(wtxn, &click_key(namespace.as_deref(), alias))?;
+        }
+        swept += expired.len();
+    }
+    Ok(swept)
+}
+
+/// Seconds since the Unix epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Parses `raw` as an absolute URL, accepting only the `http` and `https`
+/// schemes so stored links can't become `javascript:` or `file://` redirects.
+fn validate_url(raw: &str) -> Result<Url, String> {
+    let url = Url::parse(raw).map_err(|error| format!("invalid URL: {error}"))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!(
+            "unsupported URL scheme \"{scheme}\", expected http or https"
+        )),
+    }
+}
+
+/// Tries up to `MAX_ALIAS_ATTEMPTS` random aliases, returning the first one not
+/// held by a live link in `db` as of `now`.
+fn generate_free_alias(
+    db: LinkDb,
+    txn: &RoTxn,
+    rules: &AliasRules,
+    now: u64,
+) -> heed::Result<Option<String>> {
+    for _ in 0..MAX_ALIAS_ATTEMPTS {
+        let mut candidate = generate_alias(GENERATED_ALIAS_LEN);
+        // Generated aliases must survive the same folding lookups apply.
+        if rules.fold_case {
+            candidate.make_ascii_lowercase();
+        }
+        if db
+            .get(txn, &candidate)?
+            .is_none_or(|taken| taken.is_expired(now))
+        {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Limits applied to every alias, whether it's being stored or looked up.
+#[derive(Clone, Copy)]
+struct AliasRules {
+    max_len: usize,
+    /// Treat `Foo` and `foo` as the same alias.
+    fold_case: bool,
+}
+
+impl AliasRules {
+    /// Reads `SILLY_MAX_ALIAS_LEN` and `SILLY_ALIAS_CASE_SENSITIVE`
+    /// (`true`/`1` to stop lowercasing aliases).
+    fn from_env() -> Result<Self, String> {
+        let max_len = match std::env::var("SILLY_MAX_ALIAS_LEN") {
+            Ok(raw) => raw
+                .parse()
+                .ok()
+                .filter(|&len| len >= GENERATED_ALIAS_LEN)
+                .ok_or(format!(
+                    "invalid SILLY_MAX_ALIAS_LEN {raw}: expected an integer of at least {GENERATED_ALIAS_LEN}"
+                ))?,
+            Err(_) => DEFAULT_MAX_ALIAS_LEN,
+        };
+        let case_sensitive = std::env::var("SILLY_ALIAS_CASE_SENSITIVE")
+            .is_ok_and(|raw| matches!(raw.as_str(), "1" | "true"));
+
+        Ok(AliasRules {
+            max_len,
+            fold_case: !case_sensitive,
+        })
+    }
+}
+
+/// Canonicalises an alias so lookups match stored keys.
+///
+/// Surrounding whitespace is trimmed and, unless disabled, the alias is
+/// lowercased. Empty aliases, overlong ones, reserved ones and any containing
+/// `/`, `?`, `#` or control characters are rejected since they'd break routing.
+fn normalize_alias(raw: &str, rules: &AliasRules) -> Result<String, String> {
+    let alias = normalize_segment(raw, "alias", rules)?;
+    if RESERVED_ALIASES.contains(&alias.as_str()) {
+        return Err(format!("alias {alias} is reserved"));
+    }
+    Ok(alias)
+}
+
+/// Canonicalises a namespace with the same rules as an alias, additionally
+/// rejecting names that other routes already claim.
+fn normalize_namespace(raw: &str, rules: &AliasRules) -> Result<String, String> {
+    let namespace = normalize_segment(raw, "namespace", rules)?;
+    if RESERVED_NAMESPACES.contains(&namespace.as_str()) {
+        return Err(format!("namespace {namespace} is reserved"));
+    }
+    Ok(namespace)
+}
+
+/// Shared rules for a single path segment; `what` names it in errors.
+fn normalize_segment(raw: &str, what: &str, rules: &AliasRules) -> Result<String, String> {
+    let segment = raw.trim();
+    if segment.is_empty() {
+        return Err(format!("{what} must not be empty"));
+    }
+    if segment.chars().count() > rules.max_len {
+        return Err(format!(
+            "{what} must be at most {} characters",
+            rules.max_len
+        ));
+    }
+    if segment
+        .chars()
+        .any(|c| matches!(c, '/' | '?' | '#') || c.is_control())
+    {
+        return Err(format!(
+            "{what} must not contain '/', '?', '#' or control characters"
+        ));
+    }
+
+    Ok(if rules.fold_case {
+        segment.to_lowercase()
+    } else {
+        segment.to_string()
+    })
+}
+
+/// Generates a random alias of `len` characters from the base62 alphabet.
+fn generate_alias(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+async fn delete_alias(
+    State(state): State<AppState>,
+    Path(alias): Path<String>,
+) -> Result<Response, AppError> {
+    remove_link(&state, None, &alias).await
+}
+
+async fn namespaced_delete(
+    State(state): State<AppState>,
+    Path((namespace, alias)): Path<(String, String)>,
+) -> Result<Response, AppError> {
+    match normalize_namespace(&namespace, &state.aliases) {
+        Ok(namespace) => remove_link(&state, Some(&namespace), &alias).await,
+        Err(message) => Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    }
+}
+
+/// Deletes `alias` from `namespace` along with its click count.
+async fn remove_link(
+    state: &AppState,
+    namespace: Option<&str>,
+    alias: &str,
+) -> Result<Response, AppError> {
+    let alias = match normalize_alias(alias, &state.aliases) {
+        Ok(alias) => alias,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, &message)),
+    };
+    let Some(db) = state.links_db(namespace) else {
+        tracing::warn!("Namespace {:?} not found....", namespace);
+        return Ok(error_response(StatusCode::NOT_FOUND, "alias not found"));
+    };
     // Offload the blocking LMDB write to a blocking thread.
     let env = state.env.clone();
-    let db = state.db;
-    let key = url.alias.clone();
-    let val = url.url.clone();
-
-    tokio::task::spawn_blocking(move || {
-        // Begin a write transaction.
-        let mut wtxn = env.write_txn().expect("Failed to create write transaction");
-        // Insert the key-value pair.
-        db.put(&mut wtxn, key.as_str(), val.as_str())
-            .expect("Failed to write to DB");
-        // Commit the transaction.
-        wtxn.commit().expect("Failed to commit transaction");
-    })
-    .await
-    .expect("Blocking task panicked");
+    let clicks = state.clicks;
+    let key = alias.clone();
+    let click_key = click_key(namespace, &alias);
+
+    // LMDB serialises write transactions, so when two deletes race on the same
+    // alias only the first one finds the key; the second sees `false`.
+    let deleted = state
+        .writes
+        .spawn_blocking(move || -> heed::Result<bool> {
+            // Begin a write transaction.
+            let mut wtxn = env.write_txn()?;
+            let deleted = delete_link(db, clicks, &mut wtxn, &key, &click_key)?;
+            // Commit the transaction.
+            wtxn.commit()?;
+            Ok(deleted)
+        })
+        .await??;
+
+    if deleted {
+        info!("Deleted short link {}...", &alias);
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        tracing::warn!("Short link {} not found....", &alias);
+        Ok(error_response(StatusCode::NOT_FOUND, "alias not found"))
+    }
+}
 
-    (StatusCode::CREATED, Json(url))
+/// Removes `alias` and its click count within the caller's transaction,
+/// returning whether the alias existed.
+fn delete_link(
+    db: LinkDb,
+    clicks: Database<Str, U64<BigEndian>>,
+    wtxn: &mut RwTxn,
+    alias: &str,
+    click_key: &str,
+) -> heed::Result<bool> {
+    // Remove the key, if present.
+    let deleted = db.delete(wtxn, alias)?;
+    // Drop the click count too so a recreated alias starts from zero.
+    clicks.delete(wtxn, click_key)?;
+    Ok(deleted)
 }
 
 #[derive(Deserialize, Clone)]
 struct CreateShortUrl {
     url: String,
-    alias: String,
+    alias: Option<String>,
+    /// Serve a `301 Moved Permanently` instead of a `307 Temporary Redirect`.
+    ///
+    /// Browsers cache 301s aggressively, so clients that have already followed
+    /// a permanent link keep going to the old destination even after the alias
+    /// is deleted or changed.
+    #[serde(default)]
+    permanent: bool,
+    /// Stop resolving the link this many seconds after creation.
+    expires_in_secs: Option<u64>,
+    /// Store the link in its own namespace, served at `/{namespace}/{alias}`.
+    namespace: Option<String>,
 }
 
 #[derive(Serialize)]
 struct ShortUrl {
     url: String,
     alias: String,
+    permanent: bool,
+    expires_at: Option<u64>,
+    /// `None` for the default namespace.
+    namespace: Option<String>,
+    /// The full link, e.g. `https://sil.ly/alias`.
+    short_url: Option<String>,
     error: Option<String>,
 }
+
+impl ShortUrl {
+    /// Fills in `short_url` from `base` for links that were actually stored.
+    fn set_short_url(&mut self, base: Option<&Url>) {
+        if self.error.is_none() {
+            self.short_url =
+                base.map(|base| join_short_url(base, self.namespace.as_deref(), &self.alias));
+        }
+    }
+
+    /// The record to store for this link.
+    fn record(&self) -> LinkRecord {
+        LinkRecord {
+            url: self.url.clone(),
+            permanent: self.permanent,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Describes a link already in `namespace`'s db.
+    fn stored(namespace: Option<&str>, alias: &str, record: LinkRecord) -> Self {
+        ShortUrl {
+            url: record.url,
+            alias: alias.to_string(),
+            permanent: record.permanent,
+            expires_at: record.expires_at,
+            namespace: namespace.map(String::from),
+            short_url: None,
+            error: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ReverseQuery {
+    url: String,
+}
+
+/// `?namespace=` for read endpoints; omitted means the default namespace.
+#[derive(Deserialize)]
+struct NamespaceQuery {
+    namespace: Option<String>,
+}
+
+impl NamespaceQuery {
+    /// The namespace normalised by `normalize_namespace`, if one was given.
+    fn normalize(self, rules: &AliasRules) -> Result<Option<String>, String> {
+        self.namespace
+            .as_deref()
+            .map(|raw| normalize_namespace(raw, rules))
+            .transpose()
+    }
+}
+
+/// `?limit=` and `?offset=` query parameters for listing endpoints.
+#[derive(Deserialize)]
+struct Pagination {
+    #[serde(default = "default_page_limit", deserialize_with = "clamp_page_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+}
+
+fn default_page_limit() -> usize {
+    DEFAULT_PAGE_LIMIT
+}
+
+/// Caps `?limit=` at `MAX_PAGE_LIMIT` so one request can't serialise the whole db.
+fn clamp_page_limit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    usize::deserialize(deserializer).map(|limit| limit.min(MAX_PAGE_LIMIT))
+}
+
+#[derive(Serialize)]
+struct LinkStats {
+    alias: String,
+    url: String,
+    clicks: u64,
+}
+
+/// A namespace's database of alias → link record.
+type LinkDb = Database<Str, SerdeJson<LinkRecord>>;
+
+/// Links dbs by namespace, `None` being the default one.
+type LinkDbs = HashMap<Option<String>, LinkDb>;
+
+/// A create request checked by `prepare_link`: the response skeleton and
+/// requested alias, or the skeleton with `error` set if it was rejected.
+type PreparedLink = Result<(ShortUrl, Option<String>), Box<ShortUrl>>;
+
+/// The value stored against each alias in the `requests` database.
+#[derive(Serialize, Deserialize)]
+struct LinkRecord {
+    url: String,
+    #[serde(default)]
+    permanent: bool,
+    /// Absolute expiry as seconds since the Unix epoch; `None` never expires.
+    #[serde(default)]
+    expires_at: Option<u64>,
+}
+
+impl LinkRecord {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh environment; keep the guard alive for as long as the env.
+    fn temp_env(max_dbs: u32) -> (TempDir, Env) {
+        let dir = tempfile::tempdir().unwrap();
+        let env = unsafe { EnvOpenOptions::new().max_dbs(max_dbs).open(dir.path()) }.unwrap();
+        (dir, env)
+    }
+
+    fn record(url: &str) -> LinkRecord {
+        LinkRecord {
+            url: url.to_string(),
+            permanent: false,
+            expires_at: None,
+        }
+    }
+
+    fn links_and_clicks(env: &Env, wtxn: &mut RwTxn) -> (LinkDb, Database<Str, U64<BigEndian>>) {
+        let db = env.create_database(wtxn, Some("requests")).unwrap();
+        let clicks = env.create_database(wtxn, Some("clicks")).unwrap();
+        (db, clicks)
+    }
+
+    #[test]
+    fn insert_link_replaces_expired_aliases() {
+        let (_dir, env) = temp_env(2);
+        let mut wtxn = env.write_txn().unwrap();
+        let (db, clicks) = links_and_clicks(&env, &mut wtxn);
+        let expired = LinkRecord {
+            expires_at: Some(unix_now() - 1),
+            ..record("https://example.com/old")
+        };
+        db.put(&mut wtxn, "tmp", &expired).unwrap();
+        clicks.put(&mut wtxn, "tmp", &7).unwrap();
+
+        let fresh = record("https://example.com/new");
+        let inserted = insert_link(
+            db,
+            clicks,
+            &mut wtxn,
+            None,
+            Some("tmp".into()),
+            &fresh,
+            &FOLDING,
+        );
+        assert_eq!(inserted.unwrap(), Ok("tmp".to_string()));
+        assert_eq!(db.get(&wtxn, "tmp").unwrap().unwrap().url, fresh.url);
+        // The old link's clicks don't carry over.
+        assert_eq!(clicks.get(&wtxn, "tmp").unwrap(), None);
+    }
+
+    #[test]
+    fn sweep_links_only_removes_expired_links() {
+        let (_dir, env) = temp_env(3);
+        let mut wtxn = env.write_txn().unwrap();
+        let (db, clicks) = links_and_clicks(&env, &mut wtxn);
+        let team: LinkDb = env.create_database(&mut wtxn, Some("ns:team")).unwrap();
+        let now = 1_000;
+        let expiring = |expires_at| LinkRecord {
+            expires_at: Some(expires_at),
+            ..record("https://example.com/")
+        };
+        db.put(&mut wtxn, "expired", &expiring(now)).unwrap();
+        db.put(&mut wtxn, "live", &expiring(now + 1)).unwrap();
+        db.put(&mut wtxn, "forever", &record("https://example.com/"))
+            .unwrap();
+        team.put(&mut wtxn, "expired", &expiring(now - 1)).unwrap();
+        clicks.put(&mut wtxn, "expired", &1).unwrap();
+        clicks.put(&mut wtxn, "team/expired", &2).unwrap();
+        clicks.put(&mut wtxn, "live", &3).unwrap();
+
+        let dbs = [(None, db), (Some("team".to_string()), team)];
+        assert_eq!(sweep_links(&mut wtxn, &dbs, clicks, now).unwrap(), 2);
+
+        let remaining: Vec<String> = db
+            .iter(&wtxn)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.to_string())
+            .collect();
+        assert_eq!(remaining, ["forever", "live"]);
+        assert!(team.is_empty(&wtxn).unwrap());
+        assert_eq!(clicks.get(&wtxn, "expired").unwrap(), None);
+        assert_eq!(clicks.get(&wtxn, "team/expired").unwrap(), None);
+        assert_eq!(clicks.get(&wtxn, "live").unwrap(), Some(3));
+        // A second pass finds nothing left to do.
+        assert_eq!(sweep_links(&mut wtxn, &dbs, clicks, now).unwrap(), 0);
+    }
+
+    #[test]
+    fn prepare_link_rejects_zero_expiry() {
+        let payload = CreateShortUrl {
+            url: "https://example.com".into(),
+            alias: None,
+            permanent: false,
+            expires_in_secs: Some(0),
+            namespace: None,
+        };
+        let Err(rejected) = prepare_link(payload, &FOLDING) else {
+            panic!("a zero expiry should be rejected");
+        };
+        assert_eq!(
+            rejected.error.as_deref(),
+            Some("expires_in_secs must be positive")
+        );
+    }
+
+    #[test]
+    fn insert_link_rejects_taken_aliases() {
+        let (_dir, env) = temp_env(2);
+        let mut wtxn = env.write_txn().unwrap();
+        let (db, clicks) = links_and_clicks(&env, &mut wtxn);
+        let first = record("https://example.com/first");
+        let second = record("https://example.com/second");
+
+        let insert = |wtxn: &mut RwTxn, record: &LinkRecord| {
+            insert_link(db, clicks, wtxn, None, Some("abc".into()), record, &FOLDING)
+        };
+        assert_eq!(insert(&mut wtxn, &first).unwrap(), Ok("abc".to_string()));
+        let conflict = insert(&mut wtxn, &second);
+        assert_eq!(
+            conflict.unwrap(),
+            Err((StatusCode::CONFLICT, "alias already in use"))
+        );
+        // The original link is left untouched.
+        assert_eq!(db.get(&wtxn, "abc").unwrap().unwrap().url, first.url);
+    }
+
+    #[test]
+    fn add_clicks_sums_onto_existing_counts() {
+        let (_dir, env) = temp_env(1);
+        let mut wtxn = env.write_txn().unwrap();
+        let clicks: Database<Str, U64<BigEndian>> =
+            env.create_database(&mut wtxn, Some("clicks")).unwrap();
+        clicks.put(&mut wtxn, "abc", &2).unwrap();
+        wtxn.commit().unwrap();
+
+        let counts = HashMap::from([("abc".to_string(), 3), ("team/abc".to_string(), 1)]);
+        add_clicks(&env, clicks, &counts).unwrap();
+
+        let rtxn = env.read_txn().unwrap();
+        assert_eq!(clicks.get(&rtxn, "abc").unwrap(), Some(5));
+        assert_eq!(clicks.get(&rtxn, "team/abc").unwrap(), Some(1));
+    }
+
+    /// An `AppState` over the environment at `path`, set up like `main` does.
+    fn test_state(path: &std::path::Path, max_dbs: u32) -> AppState {
+        let env = unsafe { EnvOpenOptions::new().max_dbs(max_dbs).open(path) }.unwrap();
+        let mut wtxn = env.write_txn().unwrap();
+        let (db, clicks) = links_and_clicks(&env, &mut wtxn);
+        let namespaces = open_namespaces(&env, &wtxn).unwrap();
+        wtxn.commit().unwrap();
+        let (click_queue, _) = mpsc::channel(1);
+
+        AppState {
+            env: Arc::new(env),
+            db,
+            namespaces: Arc::new(RwLock::new(namespaces)),
+            clicks,
+            click_queue,
+            writes: TaskTracker::new(),
+            aliases: FOLDING,
+            base_url: None,
+            trust_proxy: false,
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            batch_limiter: Arc::new(RateLimiter::new(DEFAULT_BATCH_RATE_LIMIT, false)),
+        }
+    }
+
+    fn create(url: &str, alias: Option<&str>, namespace: Option<&str>) -> CreateShortUrl {
+        CreateShortUrl {
+            url: url.to_string(),
+            alias: alias.map(String::from),
+            permanent: false,
+            expires_in_secs: None,
+            namespace: namespace.map(String::from),
+        }
+    }
+
+    #[test]
+    fn insert_batch_reports_each_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path(), 2);
+        let prepared = [
+            create("https://example.com/a", Some("a"), None),
+            create("ftp://example.com/bad", Some("bad"), None),
+            create("https://example.com/dup", Some("dup"), None),
+            // Sees the entry above, which isn't committed yet.
+            create("https://example.com/again", Some("DUP"), None),
+            create("https://example.com/generated", None, None),
+        ]
+        .into_iter()
+        .map(|payload| prepare_link(payload, &state.aliases))
+        .collect();
+
+        let mut wtxn = state.env.write_txn().unwrap();
+        let (urls, _) = insert_batch(&state, &mut wtxn, prepared).unwrap();
+        wtxn.commit().unwrap();
+
+        let errors: Vec<Option<&str>> = urls.iter().map(|url| url.error.as_deref()).collect();
+        assert_eq!(errors[0], None);
+        assert!(errors[1].unwrap().starts_with("unsupported URL scheme"));
+        assert_eq!(errors[2], None);
+        assert_eq!(errors[3], Some("alias already in use"));
+        assert_eq!(errors[4], None);
+
+        let rtxn = state.env.read_txn().unwrap();
+        assert_eq!(state.db.len(&rtxn).unwrap(), 3);
+        let dup = state.db.get(&rtxn, "dup").unwrap().unwrap();
+        assert_eq!(dup.url, "https://example.com/dup");
+        assert!(state.db.get(&rtxn, &urls[4].alias).unwrap().is_some());
+    }
+
+    #[test]
+    fn aborted_batch_leaves_no_namespace_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path(), 3);
+        let payload = create("https://example.com/", Some("a"), Some("team"));
+        let prepared = vec![prepare_link(payload, &state.aliases)];
+
+        let mut wtxn = state.env.write_txn().unwrap();
+        let (urls, dbs) = insert_batch(&state, &mut wtxn, prepared).unwrap();
+        assert_eq!(urls[0].error, None);
+        assert!(dbs.contains_key(&Some("team".to_string())));
+        // Dropping the transaction aborts it, as a failed commit would.
+        drop(wtxn);
+
+        assert!(state.links_db(Some("team")).is_none());
+        let rtxn = state.env.read_txn().unwrap();
+        let db: Option<LinkDb> = state.env.open_database(&rtxn, Some("ns:team")).unwrap();
+        assert!(db.is_none());
+    }
+
+    #[test]
+    fn namespaces_are_created_lazily_and_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path(), 3);
+        assert!(state.links_db(Some("team")).is_none());
+
+        let mut wtxn = state.env.write_txn().unwrap();
+        let db = state
+            .create_links_db(&mut wtxn, Some("team"))
+            .unwrap()
+            .unwrap();
+        db.put(&mut wtxn, "abc", &record("https://example.com/"))
+            .unwrap();
+        wtxn.commit().unwrap();
+        state.cache_links_db(Some("team"), db);
+        assert!(state.links_db(Some("team")).is_some());
+        // The default namespace never needs creating.
+        let mut wtxn = state.env.write_txn().unwrap();
+        let default = state.create_links_db(&mut wtxn, None).unwrap().unwrap();
+        default
+            .put(&mut wtxn, "abc", &record("https://example.com/"))
+            .unwrap();
+        assert!(state.db.get(&wtxn, "abc").unwrap().is_some());
+        drop(wtxn);
+
+        // Restart: `open_namespaces` finds the db from the previous run.
+        drop(state);
+        let state = test_state(dir.path(), 3);
+        let db = state.links_db(Some("team")).unwrap();
+        let rtxn = state.env.read_txn().unwrap();
+        assert!(db.get(&rtxn, "abc").unwrap().is_some());
+    }
+
+    #[test]
+    fn namespaces_are_rejected_once_every_db_is_taken() {
+        let dir = tempfile::tempdir().unwrap();
+        // `requests` and `clicks` leave room for a single namespace.
+        let state = test_state(dir.path(), 3);
+
+        let mut wtxn = state.env.write_txn().unwrap();
+        let db = state
+            .create_links_db(&mut wtxn, Some("one"))
+            .unwrap()
+            .unwrap();
+        wtxn.commit().unwrap();
+        state.cache_links_db(Some("one"), db);
+
+        let mut wtxn = state.env.write_txn().unwrap();
+        let full = state.create_links_db(&mut wtxn, Some("two")).unwrap();
+        assert_eq!(
+            full.err(),
+            Some((StatusCode::INSUFFICIENT_STORAGE, "namespace limit reached"))
+        );
+        // Existing namespaces keep working, and the transaction is still usable.
+        let again = state.create_links_db(&mut wtxn, Some("one")).unwrap();
+        assert!(again.is_ok());
+        wtxn.commit().unwrap();
+    }
+
+    #[test]
+    fn aborted_namespace_creates_are_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path(), 3);
+
+        let wtxn_db = {
+            let mut wtxn = state.env.write_txn().unwrap();
+            state.create_links_db(&mut wtxn, Some("team")).unwrap()
+        };
+        assert!(wtxn_db.is_ok());
+        assert!(state.links_db(Some("team")).is_none());
+
+        // The slot was released with the transaction, so creating it again works.
+        let mut wtxn = state.env.write_txn().unwrap();
+        let db = state
+            .create_links_db(&mut wtxn, Some("team"))
+            .unwrap()
+            .unwrap();
+        wtxn.commit().unwrap();
+        state.cache_links_db(Some("team"), db);
+        assert!(state.links_db(Some("team")).is_some());
+    }
+
+    #[test]
+    fn delete_link_reports_missing_aliases() {
+        let (_dir, env) = temp_env(2);
+        let mut wtxn = env.write_txn().unwrap();
+        let (db, clicks) = links_and_clicks(&env, &mut wtxn);
+        db.put(&mut wtxn, "abc", &record("https://example.com/"))
+            .unwrap();
+        clicks.put(&mut wtxn, "abc", &3).unwrap();
+        wtxn.commit().unwrap();
+
+        for expected in [true, false] {
+            let mut wtxn = env.write_txn().unwrap();
+            let deleted = delete_link(db, clicks, &mut wtxn, "abc", "abc").unwrap();
+            wtxn.commit().unwrap();
+            assert_eq!(deleted, expected);
+        }
+
+        let rtxn = env.read_txn().unwrap();
+        assert!(db.get(&rtxn, "abc").unwrap().is_none());
+        assert!(clicks.get(&rtxn, "abc").unwrap().is_none());
+    }
+
+    #[test]
+    fn validate_url_accepts_http_and_https() {
+        assert_eq!(
+            validate_url("http://example.com/a?b=c").unwrap().as_str(),
+            "http://example.com/a?b=c"
+        );
+        assert_eq!(
+            validate_url("https://example.com").unwrap().as_str(),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn validate_url_rejects_other_schemes_and_relative_urls() {
+        for raw in [
+            "ftp://example.com/file",
+            "file:///etc/passwd",
+            "javascript:alert(1)",
+            "/relative/path",
+        ] {
+            assert!(validate_url(raw).is_err(), "{raw} should be rejected");
+        }
+    }
+
+    fn headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn client_ip_ignores_forwarded_for_unless_trusted() {
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let limiter = RateLimiter::new(DEFAULT_RATE_LIMIT, false);
+        assert_eq!(limiter.client_ip(&headers("10.0.0.1"), peer), peer.ip());
+    }
+
+    #[test]
+    fn client_ip_takes_the_hop_appended_by_the_proxy() {
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let limiter = RateLimiter::new(DEFAULT_RATE_LIMIT, true);
+        let proxy_hop: IpAddr = "192.0.2.7".parse().unwrap();
+
+        assert_eq!(
+            limiter.client_ip(&headers("10.0.0.1, 10.0.0.2, 192.0.2.7"), peer),
+            proxy_hop
+        );
+        assert_eq!(limiter.client_ip(&headers(" 192.0.2.7 "), peer), proxy_hop);
+        // Garbage or a missing header falls back to the socket address.
+        assert_eq!(
+            limiter.client_ip(&headers("10.0.0.1, junk"), peer),
+            peer.ip()
+        );
+        assert_eq!(limiter.client_ip(&HeaderMap::new(), peer), peer.ip());
+    }
+
+    #[test]
+    fn rate_limiter_refills_over_time() {
+        let limiter = RateLimiter::new(2, false);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(ip, 1, start).is_ok());
+        assert!(limiter.check_at(ip, 1, start).is_ok());
+        // Two per minute refill one token every 30s.
+        let wait = limiter.check_at(ip, 1, start).unwrap_err();
+        assert_eq!(retry_after_secs(wait), 30);
+        // Buckets are per client.
+        assert!(limiter.check_at(other, 1, start).is_ok());
+
+        let wait = limiter
+            .check_at(ip, 1, start + Duration::from_secs(20))
+            .unwrap_err();
+        assert_eq!(retry_after_secs(wait), 10);
+        assert!(limiter
+            .check_at(ip, 1, start + Duration::from_secs(30))
+            .is_ok());
+        assert!(limiter
+            .check_at(ip, 1, start + Duration::from_secs(30))
+            .is_err());
+    }
+
+    #[test]
+    fn rate_limiter_charges_cost_tokens() {
+        let limiter = RateLimiter::new(10, false);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(ip, 6, start).is_ok());
+        // Four tokens left, one every 6s: two more are 12s away.
+        let wait = limiter.check_at(ip, 6, start).unwrap_err();
+        assert_eq!(retry_after_secs(wait), 12);
+        assert!(limiter.check_at(ip, 4, start).is_ok());
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        assert_eq!(retry_after_secs(Duration::from_secs(3)), 3);
+        assert_eq!(retry_after_secs(Duration::from_millis(3001)), 4);
+        assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+    }
+
+    const FOLDING: AliasRules = AliasRules {
+        max_len: 8,
+        fold_case: true,
+    };
+
+    #[test]
+    fn normalize_segment_trims_and_folds_case() {
+        assert_eq!(normalize_alias("  MyLink\t", &FOLDING).unwrap(), "mylink");
+
+        let case_sensitive = AliasRules {
+            fold_case: false,
+            ..FOLDING
+        };
+        assert_eq!(
+            normalize_alias(" MyLink ", &case_sensitive).unwrap(),
+            "MyLink"
+        );
+    }
+
+    #[test]
+    fn normalize_segment_rejects_empty_input() {
+        for raw in ["", "   ", "\t\n"] {
+            assert_eq!(
+                normalize_alias(raw, &FOLDING).unwrap_err(),
+                "alias must not be empty"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_segment_rejects_routing_and_control_characters() {
+        for raw in ["a/b", "a?b", "a#b", "a\u{7}b", "a\u{7f}b"] {
+            assert!(
+                normalize_alias(raw, &FOLDING).is_err(),
+                "{raw:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_segment_enforces_max_len_in_characters() {
+        assert_eq!(normalize_alias("abcdefgh", &FOLDING).unwrap(), "abcdefgh");
+        // Length is counted after trimming and in chars, not bytes.
+        assert_eq!(normalize_alias(" éééééééé ", &FOLDING).unwrap(), "éééééééé");
+        assert_eq!(
+            normalize_alias("abcdefghi", &FOLDING).unwrap_err(),
+            "alias must be at most 8 characters"
+        );
+    }
+
+    #[test]
+    fn normalize_alias_rejects_reserved_names() {
+        assert_eq!(
+            normalize_alias(" HealthZ ", &FOLDING).unwrap_err(),
+            "alias healthz is reserved"
+        );
+        assert!(normalize_alias("metrics", &FOLDING).is_err());
+    }
+
+    #[test]
+    fn normalize_namespace_rejects_reserved_names() {
+        assert_eq!(normalize_namespace(" Team ", &FOLDING).unwrap(), "team");
+        assert!(normalize_namespace("API", &FOLDING).is_err());
+        assert!(normalize_namespace("stats", &FOLDING).is_err());
+    }
+
+    #[test]
+    fn bearer_token_matches_the_scheme_case_insensitively() {
+        for raw in ["Bearer s3cret", "bearer s3cret", "BEARER  s3cret "] {
+            let value = HeaderValue::from_static(raw);
+            assert_eq!(bearer_token(&value), Some("s3cret"), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn bearer_token_rejects_malformed_credentials() {
+        for raw in [
+            "s3cret",
+            "Bearer",
+            "Bearer   ",
+            "Basic czNjcmV0",
+            "Bearers3cret",
+        ] {
+            let value = HeaderValue::from_static(raw);
+            assert_eq!(bearer_token(&value), None, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn join_short_url_handles_trailing_slashes() {
+        for (base, expected) in [
+            ("https://sil.ly", "https://sil.ly/abc"),
+            ("https://sil.ly/", "https://sil.ly/abc"),
+            ("https://sil.ly/p", "https://sil.ly/p/abc"),
+            ("https://sil.ly/p/", "https://sil.ly/p/abc"),
+        ] {
+            let base = Url::parse(base).unwrap();
+            assert_eq!(join_short_url(&base, None, "abc"), expected);
+        }
+    }
+
+    #[test]
+    fn join_short_url_includes_the_namespace() {
+        let base = Url::parse("https://sil.ly/p/").unwrap();
+        assert_eq!(
+            join_short_url(&base, Some("team"), "abc"),
+            "https://sil.ly/p/team/abc"
+        );
+    }
+
+    #[test]
+    fn request_base_url_prefers_host_over_authority() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("sil.ly"));
+        let uri: Uri = "http://internal:3000/".parse().unwrap();
+
+        let base = request_base_url(&headers, &uri, false).unwrap();
+        assert_eq!(base.as_str(), "http://sil.ly/");
+    }
+
+    #[test]
+    fn request_base_url_falls_back_to_authority() {
+        let uri: Uri = "https://sil.ly/".parse().unwrap();
+        let base = request_base_url(&HeaderMap::new(), &uri, false).unwrap();
+        assert_eq!(base.as_str(), "https://sil.ly/");
+
+        let uri: Uri = "/".parse().unwrap();
+        assert!(request_base_url(&HeaderMap::new(), &uri, false).is_none());
+    }
+
+    #[test]
+    fn request_base_url_honours_forwarded_proto_only_when_trusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("sil.ly"));
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        let uri: Uri = "/".parse().unwrap();
+
+        let trusted = request_base_url(&headers, &uri, true).unwrap();
+        assert_eq!(trusted.as_str(), "https://sil.ly/");
+        let untrusted = request_base_url(&headers, &uri, false).unwrap();
+        assert_eq!(untrusted.as_str(), "http://sil.ly/");
+    }
+
+    #[test]
+    fn method_label_folds_unknown_methods() {
+        assert_eq!(method_label(&Method::GET), "GET");
+        assert_eq!(method_label(&Method::DELETE), "DELETE");
+        let custom = Method::from_bytes(b"FOOBAR123").unwrap();
+        assert_eq!(method_label(&custom), "other");
+    }
+
+    #[test]
+    fn pagination_clamps_limit() {
+        let uri = "/api/links?limit=1000000000000&offset=5".parse().unwrap();
+        let Query(page) = Query::<Pagination>::try_from_uri(&uri).unwrap();
+        assert_eq!((page.limit, page.offset), (MAX_PAGE_LIMIT, 5));
+
+        let uri = "/api/links".parse().unwrap();
+        let Query(page) = Query::<Pagination>::try_from_uri(&uri).unwrap();
+        assert_eq!((page.limit, page.offset), (DEFAULT_PAGE_LIMIT, 0));
+    }
+}